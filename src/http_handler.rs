@@ -1,6 +1,7 @@
 use lambda_http::{Body, Error, Request, RequestExt, Response};
 use serde::Serialize;
-use crate::screenshot::ScreenshotService;
+use crate::nsfw::{check_nsfw, NsfwScores, TractNsfwClassifier};
+use crate::screenshot::{ClipRegion, ScreenshotService};
 use std::env;
 
 #[derive(Serialize)]
@@ -16,6 +17,13 @@ struct SuccessResponse {
     content_type: String,
 }
 
+#[derive(Serialize)]
+struct NsfwRejectedResponse {
+    error: String,
+    message: String,
+    scores: NsfwScores,
+}
+
 /// Main function handler for the screenshot API
 pub(crate) async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
     // --- Authentication ---
@@ -128,17 +136,55 @@ async fn handle_screenshot_request(
         .unwrap_or("png");
 
     // Validate format
-    if format != "png" && format != "jpeg" {
-        return Err("Invalid format. Only 'png' and 'jpeg' are supported".into());
+    if format != "png" && format != "jpeg" && format != "webp" {
+        return Err("Invalid format. Only 'png', 'jpeg' and 'webp' are supported".into());
     }
 
+    let quality = params
+        .first("quality")
+        .and_then(|q| q.parse::<u8>().ok());
+
+    let full_page = params
+        .first("full_page")
+        .or_else(|| params.first("fullscreen"))
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+
+    let clip = parse_clip_region(&params)?;
+
+    let color_scheme = params.first("color_scheme");
+    let reduced_motion = params
+        .first("reduced_motion")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+
+    let extra_flags: Vec<String> = params.all("flag").unwrap_or_default()
+        .into_iter()
+        .map(|f| f.to_string())
+        .collect();
+
+    let device = params.first("device");
+    let scale_factor = params
+        .first("scale_factor")
+        .and_then(|s| s.parse::<f64>().ok());
+    let mobile = params
+        .first("mobile")
+        .map(|v| v == "true" || v == "1");
+
+    let check_nsfw_requested = params
+        .first("check_nsfw")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
+
+    let want_raw_response = wants_raw_image(&event, &params);
+
     // Handle the request based on method
     match event.method().as_str() {
-        "GET" => take_screenshot(url, Some(width), Some(height), Some(wait_time), format).await,
+        "GET" => take_screenshot(url, Some(width), Some(height), Some(wait_time), format, quality, full_page, clip, color_scheme, reduced_motion, extra_flags, device, scale_factor, mobile, check_nsfw_requested, want_raw_response).await,
         "POST" => {
             // For POST, we could accept JSON body with more complex parameters
             // For now, just use the same logic as GET
-            take_screenshot(url, Some(width), Some(height), Some(wait_time), format).await
+            take_screenshot(url, Some(width), Some(height), Some(wait_time), format, quality, full_page, clip, color_scheme, reduced_motion, extra_flags, device, scale_factor, mobile, check_nsfw_requested, want_raw_response).await
         }
         _ => {
             let error_response = ErrorResponse {
@@ -156,35 +202,128 @@ async fn handle_screenshot_request(
     }
 }
 
+/// Decide whether the caller wants raw image bytes back instead of the base64 JSON envelope:
+/// either an explicit `response=binary`/`response=raw` query param, or an `Accept: image/*` header.
+fn wants_raw_image(event: &Request, params: &lambda_http::request::QueryStringParameters) -> bool {
+    if let Some(response) = params.first("response") {
+        if response == "binary" || response == "raw" {
+            return true;
+        }
+    }
+
+    event
+        .headers()
+        .get("accept")
+        .and_then(|v| v.to_str().ok())
+        .map(|accept| accept.contains("image/") && !accept.contains("application/json"))
+        .unwrap_or(false)
+}
+
+/// Parse the four `clip_*` query parameters into a `ClipRegion`, requiring all or none
+fn parse_clip_region(
+    params: &lambda_http::request::QueryStringParameters,
+) -> Result<Option<ClipRegion>, Box<dyn std::error::Error + Send + Sync>> {
+    let clip_x = params.first("clip_x").and_then(|v| v.parse::<f64>().ok());
+    let clip_y = params.first("clip_y").and_then(|v| v.parse::<f64>().ok());
+    let clip_width = params.first("clip_width").and_then(|v| v.parse::<f64>().ok());
+    let clip_height = params.first("clip_height").and_then(|v| v.parse::<f64>().ok());
+
+    match (clip_x, clip_y, clip_width, clip_height) {
+        (None, None, None, None) => Ok(None),
+        (Some(x), Some(y), Some(width), Some(height)) => {
+            if width <= 0.0 || height <= 0.0 {
+                return Err("clip_width and clip_height must be positive".into());
+            }
+            Ok(Some(ClipRegion { x, y, width, height }))
+        }
+        _ => Err("clip_x, clip_y, clip_width and clip_height must all be supplied together".into()),
+    }
+}
+
 async fn take_screenshot(
     url: &str,
     width: Option<u32>,
     height: Option<u32>,
     wait_time: Option<u64>,
     format: &str,
+    quality: Option<u8>,
+    full_page: bool,
+    clip: Option<ClipRegion>,
+    color_scheme: Option<&str>,
+    reduced_motion: bool,
+    extra_flags: Vec<String>,
+    device: Option<&str>,
+    scale_factor: Option<f64>,
+    mobile: Option<bool>,
+    check_nsfw_requested: bool,
+    want_raw_response: bool,
 ) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
-    
+
     // Create screenshot service
     let screenshot_service = ScreenshotService::new()
         .map_err(|e| format!("Failed to initialize browser: {}", e))?;
 
     // Take screenshot
     let screenshot_data = screenshot_service
-        .take_screenshot(url, width, height, wait_time)
+        .take_screenshot(url, width, height, wait_time, format, quality, full_page, clip, color_scheme, reduced_motion, extra_flags, device, scale_factor, mobile)
         .await
         .map_err(|e| format!("Failed to take screenshot: {}", e))?;
 
+    // Optionally gate the response behind an NSFW classifier before returning it
+    let nsfw_enabled = check_nsfw_requested
+        || env::var("CHECK_IF_NSFW").map(|v| v == "true" || v == "1").unwrap_or(false);
+
+    if nsfw_enabled {
+        let threshold: f32 = env::var("NSFW_THRESHOLD")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0.8);
+        let model_path = env::var("NSFW_MODEL_PATH").unwrap_or_else(|_| "/opt/model/nsfw.onnx".to_string());
+        let classifier = TractNsfwClassifier::new(model_path);
+
+        if let Some(scores) = check_nsfw(&classifier, &screenshot_data, threshold)
+            .map_err(|e| format!("NSFW check failed: {}", e))?
+        {
+            let rejected = NsfwRejectedResponse {
+                error: "NSFW_CONTENT_DETECTED".to_string(),
+                message: "Screenshot was withheld because it crossed the configured NSFW threshold".to_string(),
+                scores,
+            };
+
+            let resp = Response::builder()
+                .status(422)
+                .header("content-type", "application/json")
+                .body(serde_json::to_string(&rejected)?.into())
+                .map_err(Box::new)?;
+            return Ok(resp);
+        }
+    }
+
     // Check if client wants JSON response or direct image
     let content_type = match format {
         "png" => "image/png",
         "jpeg" => "image/jpeg",
+        "webp" => "image/webp",
         _ => "image/png",
     };
 
+    if want_raw_response {
+        let resp = Response::builder()
+            .status(200)
+            .header("content-type", content_type)
+            .header("access-control-allow-origin", "*") // Enable CORS
+            .header("access-control-allow-methods", "GET, POST, OPTIONS")
+            .header("access-control-allow-headers", "Content-Type")
+            .body(Body::Binary(screenshot_data))
+            .map_err(Box::new)?;
+
+        return Ok(resp);
+    }
+
     // Encode as base64 for JSON response
     use base64::Engine;
     let encoded_image = base64::engine::general_purpose::STANDARD.encode(&screenshot_data);
-    
+
     let success_response = SuccessResponse {
         success: true,
         data: encoded_image,
@@ -199,7 +338,7 @@ async fn take_screenshot(
         .header("access-control-allow-headers", "Content-Type")
         .body(serde_json::to_string(&success_response)?.into())
         .map_err(Box::new)?;
-    
+
     Ok(resp)
 }
 
@@ -214,4 +353,48 @@ mod tests {
         let response = function_handler(request).await.unwrap();
         assert_eq!(response.status(), 500);
     }
+
+    #[test]
+    fn parse_clip_region_requires_all_four_params_together() {
+        let params = lambda_http::request::QueryStringParameters::from(vec![
+            ("clip_x".to_string(), "0".to_string()),
+            ("clip_y".to_string(), "0".to_string()),
+        ]);
+        assert!(parse_clip_region(&params).is_err());
+    }
+
+    #[test]
+    fn parse_clip_region_returns_none_when_absent() {
+        let params = lambda_http::request::QueryStringParameters::default();
+        assert_eq!(parse_clip_region(&params).unwrap(), None);
+    }
+
+    #[test]
+    fn parse_clip_region_builds_region_when_all_present() {
+        let params = lambda_http::request::QueryStringParameters::from(vec![
+            ("clip_x".to_string(), "10".to_string()),
+            ("clip_y".to_string(), "20".to_string()),
+            ("clip_width".to_string(), "100".to_string()),
+            ("clip_height".to_string(), "200".to_string()),
+        ]);
+        let clip = parse_clip_region(&params).unwrap().unwrap();
+        assert_eq!((clip.x, clip.y, clip.width, clip.height), (10.0, 20.0, 100.0, 200.0));
+    }
+
+    #[test]
+    fn wants_raw_image_true_for_response_binary_param() {
+        let event = Request::default();
+        let params = lambda_http::request::QueryStringParameters::from(vec![(
+            "response".to_string(),
+            "binary".to_string(),
+        )]);
+        assert!(wants_raw_image(&event, &params));
+    }
+
+    #[test]
+    fn wants_raw_image_false_by_default() {
+        let event = Request::default();
+        let params = event.query_string_parameters();
+        assert!(!wants_raw_image(&event, &params));
+    }
 }
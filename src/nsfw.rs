@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use thiserror::Error;
+use tract_onnx::prelude::*;
+
+#[derive(Error, Debug)]
+pub enum NsfwError {
+    #[error("Failed to decode image: {0}")]
+    Decode(String),
+    #[error("Classifier failed to run: {0}")]
+    Inference(String),
+}
+
+/// Per-class probabilities produced by an NSFW classifier
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NsfwScores {
+    pub neutral: f32,
+    pub suggestive: f32,
+    pub explicit: f32,
+}
+
+impl NsfwScores {
+    /// The larger of the two non-neutral probabilities ("suggestive" and "explicit")
+    pub fn unsafe_score(&self) -> f32 {
+        self.suggestive.max(self.explicit)
+    }
+}
+
+/// A pluggable image safety classifier, so the underlying model can be swapped
+/// (e.g. an embedded ONNX/tract model in production, a stub in tests).
+pub trait NsfwClassifier {
+    fn classify(&self, image_bytes: &[u8]) -> Result<NsfwScores, NsfwError>;
+}
+
+/// A loaded, optimized ONNX graph ready to run inference
+type LoadedModel = TypedRunnableModel<Graph<TypedFact, Box<dyn TypedOp>>>;
+
+/// Process-wide cache of loaded models, keyed by path, so the (expensive) load + optimize
+/// pass only happens once per Lambda execution environment rather than once per invocation.
+fn model_cache() -> &'static Mutex<HashMap<PathBuf, Arc<LoadedModel>>> {
+    static CACHE: OnceLock<Mutex<HashMap<PathBuf, Arc<LoadedModel>>>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn load_model(model_path: &std::path::Path) -> Result<Arc<LoadedModel>, NsfwError> {
+    let cache = model_cache();
+    if let Some(model) = cache.lock().unwrap().get(model_path) {
+        return Ok(model.clone());
+    }
+
+    let model = tract_onnx::onnx()
+        .model_for_path(model_path)
+        .and_then(|m| m.into_optimized())
+        .and_then(|m| m.into_runnable())
+        .map_err(|e| NsfwError::Inference(format!("Failed to load model: {}", e)))?;
+    let model = Arc::new(model);
+
+    cache.lock().unwrap().insert(model_path.to_path_buf(), model.clone());
+    Ok(model)
+}
+
+/// Classifier backed by an embedded ONNX model, run through `tract`.
+///
+/// The model file itself is not part of this crate's source tree; it is expected
+/// to be bundled into the Lambda deployment package alongside the Chromium layer.
+/// The loaded/optimized model is cached process-wide (see `model_cache`) so repeat
+/// invocations in the same warm Lambda environment skip the costly reload.
+pub struct TractNsfwClassifier {
+    model_path: std::path::PathBuf,
+}
+
+impl TractNsfwClassifier {
+    pub fn new(model_path: impl Into<std::path::PathBuf>) -> Self {
+        Self {
+            model_path: model_path.into(),
+        }
+    }
+}
+
+impl NsfwClassifier for TractNsfwClassifier {
+    fn classify(&self, image_bytes: &[u8]) -> Result<NsfwScores, NsfwError> {
+        let image = image::load_from_memory(image_bytes)
+            .map_err(|e| NsfwError::Decode(e.to_string()))?;
+
+        let model = load_model(&self.model_path)?;
+
+        let resized = image.resize_exact(224, 224, image::imageops::FilterType::Triangle);
+        let input = tract_ndarray::Array4::from_shape_fn((1, 3, 224, 224), |(_, c, y, x)| {
+            resized.get_pixel(x as u32, y as u32).0[c] as f32 / 255.0
+        });
+
+        let outputs = model
+            .run(tvec![input.into()])
+            .map_err(|e| NsfwError::Inference(format!("Inference failed: {}", e)))?;
+
+        let scores = outputs[0]
+            .to_array_view::<f32>()
+            .map_err(|e| NsfwError::Inference(e.to_string()))?;
+
+        Ok(NsfwScores {
+            neutral: scores[[0, 0]],
+            suggestive: scores[[0, 1]],
+            explicit: scores[[0, 2]],
+        })
+    }
+}
+
+/// Run `image_bytes` through `classifier` and compare against `threshold`.
+///
+/// Returns `Ok(Some(scores))` when the content crosses the threshold (the caller
+/// should reject the response), `Ok(None)` when it's within bounds.
+pub fn check_nsfw(
+    classifier: &dyn NsfwClassifier,
+    image_bytes: &[u8],
+    threshold: f32,
+) -> Result<Option<NsfwScores>, NsfwError> {
+    let scores = classifier.classify(image_bytes)?;
+    if scores.unsafe_score() >= threshold {
+        Ok(Some(scores))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StubClassifier {
+        scores: NsfwScores,
+    }
+
+    impl NsfwClassifier for StubClassifier {
+        fn classify(&self, _image_bytes: &[u8]) -> Result<NsfwScores, NsfwError> {
+            Ok(self.scores.clone())
+        }
+    }
+
+    #[test]
+    fn unsafe_score_is_the_max_of_suggestive_and_explicit() {
+        let scores = NsfwScores {
+            neutral: 0.1,
+            suggestive: 0.7,
+            explicit: 0.2,
+        };
+        assert_eq!(scores.unsafe_score(), 0.7);
+    }
+
+    #[test]
+    fn check_nsfw_passes_content_below_threshold() {
+        let classifier = StubClassifier {
+            scores: NsfwScores {
+                neutral: 0.9,
+                suggestive: 0.05,
+                explicit: 0.05,
+            },
+        };
+
+        let result = check_nsfw(&classifier, &[], 0.5).unwrap();
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn check_nsfw_flags_content_at_threshold() {
+        let classifier = StubClassifier {
+            scores: NsfwScores {
+                neutral: 0.5,
+                suggestive: 0.5,
+                explicit: 0.0,
+            },
+        };
+
+        let result = check_nsfw(&classifier, &[], 0.5).unwrap();
+        assert!(result.is_some());
+    }
+
+    #[test]
+    fn check_nsfw_flags_content_above_threshold() {
+        let classifier = StubClassifier {
+            scores: NsfwScores {
+                neutral: 0.05,
+                suggestive: 0.05,
+                explicit: 0.9,
+            },
+        };
+
+        let result = check_nsfw(&classifier, &[], 0.5).unwrap();
+        assert!(result.unwrap().unsafe_score() > 0.5);
+    }
+}
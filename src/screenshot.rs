@@ -3,7 +3,7 @@ use headless_chrome::protocol::cdp::Page::CaptureScreenshotFormatOption;
 use thiserror::Error;
 use std::time::Duration;
 use std::path::PathBuf;
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::env;
 
 #[derive(Error, Debug)]
@@ -20,6 +20,53 @@ pub enum ScreenshotError {
     ChromeNotFound(String),
 }
 
+/// A caller-specified rectangle to capture instead of the full viewport
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClipRegion {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// Emulated device metrics applied on top of (or instead of) the plain viewport
+#[derive(Debug, Clone)]
+pub struct DeviceEmulation {
+    pub width: u32,
+    pub height: u32,
+    pub device_scale_factor: f64,
+    pub mobile: bool,
+    pub user_agent: String,
+}
+
+/// Built-in device presets, keyed by the `device` query parameter
+fn device_preset(name: &str) -> Option<DeviceEmulation> {
+    match name {
+        "iphone" => Some(DeviceEmulation {
+            width: 390,
+            height: 844,
+            device_scale_factor: 3.0,
+            mobile: true,
+            user_agent: "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.0 Mobile/15E148 Safari/604.1".to_string(),
+        }),
+        "pixel" => Some(DeviceEmulation {
+            width: 412,
+            height: 915,
+            device_scale_factor: 2.625,
+            mobile: true,
+            user_agent: "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Mobile Safari/537.36".to_string(),
+        }),
+        "ipad" => Some(DeviceEmulation {
+            width: 820,
+            height: 1180,
+            device_scale_factor: 2.0,
+            mobile: true,
+            user_agent: "Mozilla/5.0 (iPad; CPU OS 17_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.0 Mobile/15E148 Safari/604.1".to_string(),
+        }),
+        _ => None,
+    }
+}
+
 pub struct ScreenshotService;
 
 impl ScreenshotService {
@@ -44,6 +91,16 @@ impl ScreenshotService {
         width: Option<u32>,
         height: Option<u32>,
         wait_time: Option<u64>,
+        format: &str,
+        quality: Option<u8>,
+        full_page: bool,
+        clip: Option<ClipRegion>,
+        color_scheme: Option<&str>,
+        reduced_motion: bool,
+        extra_flags: Vec<String>,
+        device: Option<&str>,
+        scale_factor: Option<f64>,
+        mobile: Option<bool>,
     ) -> Result<Vec<u8>, ScreenshotError> {
         // Validate URL
         url::Url::parse(url)
@@ -117,9 +174,14 @@ impl ScreenshotService {
             OsStr::new("--user-data-dir=/tmp/chrome-user-data"),
         ];
 
+        let allowed_flags = Self::validate_extra_flags(&extra_flags)?;
+
+        let mut all_args: Vec<OsString> = chrome_args.iter().map(|a| a.to_os_string()).collect();
+        all_args.extend(allowed_flags.into_iter().map(OsString::from));
+
         // Launch browser with retry logic
         println!("Launching browser with path: {:?}", chrome_path);
-        let browser = launch_browser_with_retry(chrome_path.clone(), chrome_args.clone(), 3)
+        let browser = launch_browser_with_retry(chrome_path.clone(), all_args, 3)
             .map_err(|e| ScreenshotError::BrowserLaunch(format!("Browser launch failed after retries: {}", e)))?;
         
         println!("Browser launched successfully");
@@ -128,17 +190,171 @@ impl ScreenshotService {
         let validated_width = Self::validate_dimension(width.unwrap_or(1920), "width")?;
         let validated_height = Self::validate_dimension(height.unwrap_or(1080), "height")?;
 
+        let screenshot_format = Self::parse_format(format)?;
+        let validated_quality = Self::validate_quality(quality, &screenshot_format)?;
+        Self::validate_color_scheme(color_scheme)?;
+        let device_emulation = Self::resolve_device_emulation(
+            device,
+            scale_factor,
+            mobile,
+            validated_width,
+            validated_height,
+        )?;
+
         let result = self.capture_screenshot_internal(
-            &browser, 
-            url, 
-            validated_width, 
-            validated_height, 
-            wait_time.unwrap_or(1000)
+            &browser,
+            url,
+            validated_width,
+            validated_height,
+            wait_time.unwrap_or(1000),
+            screenshot_format,
+            validated_quality,
+            full_page,
+            clip,
+            color_scheme,
+            reduced_motion,
+            device_emulation,
         );
 
         result
     }
 
+    /// Map the caller-supplied format string to a CDP capture format
+    fn parse_format(format: &str) -> Result<CaptureScreenshotFormatOption, ScreenshotError> {
+        match format {
+            "png" => Ok(CaptureScreenshotFormatOption::Png),
+            "jpeg" => Ok(CaptureScreenshotFormatOption::Jpeg),
+            "webp" => Ok(CaptureScreenshotFormatOption::Webp),
+            other => Err(ScreenshotError::InvalidUrl(format!(
+                "Unsupported format: {}",
+                other
+            ))),
+        }
+    }
+
+    /// Quality only makes sense for lossy formats, so it is dropped for PNG; values outside
+    /// the 0-100 CDP range are rejected
+    fn validate_quality(
+        quality: Option<u8>,
+        format: &CaptureScreenshotFormatOption,
+    ) -> Result<Option<u8>, ScreenshotError> {
+        match (quality, format) {
+            (None, _) => Ok(None),
+            (Some(_), CaptureScreenshotFormatOption::Png) => Ok(None),
+            (Some(q), _) => {
+                if q > 100 {
+                    return Err(ScreenshotError::InvalidUrl(format!(
+                        "quality must be between 0 and 100 (got {})",
+                        q
+                    )));
+                }
+                Ok(Some(q))
+            }
+        }
+    }
+
+    /// Reject any caller-supplied Chrome switch whose name isn't in `ALLOWED_CHROME_FLAGS`.
+    /// The env var is a comma-separated list of switch names (e.g. `--lang,--force-device-scale-factor`);
+    /// switches are matched on the part before `=` so `--lang=fr` is allowed by listing `--lang`.
+    fn validate_extra_flags(extra_flags: &[String]) -> Result<Vec<String>, ScreenshotError> {
+        if extra_flags.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let allowed_flags_env = env::var("ALLOWED_CHROME_FLAGS").unwrap_or_default();
+        let allowlist: Vec<&str> = allowed_flags_env
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        for flag in extra_flags {
+            let name = flag.split('=').next().unwrap_or(flag);
+            if !allowlist.contains(&name) {
+                return Err(ScreenshotError::InvalidUrl(format!(
+                    "Chrome flag '{}' is not in ALLOWED_CHROME_FLAGS",
+                    name
+                )));
+            }
+        }
+
+        Ok(extra_flags.to_vec())
+    }
+
+    /// Resolve the `device`/`scale_factor`/`mobile` parameters into concrete emulated metrics.
+    /// `scale_factor` and `mobile` act as escape hatches that override whatever a named preset
+    /// sets; absent a `device` preset, they apply on top of the caller's own validated viewport
+    /// dimensions rather than silently replacing them.
+    fn resolve_device_emulation(
+        device: Option<&str>,
+        scale_factor: Option<f64>,
+        mobile: Option<bool>,
+        width: u32,
+        height: u32,
+    ) -> Result<Option<DeviceEmulation>, ScreenshotError> {
+        let preset = match device {
+            None => None,
+            Some(name) => Some(device_preset(name).ok_or_else(|| {
+                ScreenshotError::InvalidUrl(format!("Unknown device preset: {}", name))
+            })?),
+        };
+
+        if preset.is_none() && scale_factor.is_none() && mobile.is_none() {
+            return Ok(None);
+        }
+
+        let mut emulation = preset.unwrap_or(DeviceEmulation {
+            width,
+            height,
+            device_scale_factor: 1.0,
+            mobile: false,
+            user_agent: String::new(),
+        });
+
+        if let Some(scale) = scale_factor {
+            emulation.device_scale_factor = Self::validate_scale_factor(scale)?;
+        }
+        if let Some(mobile) = mobile {
+            emulation.mobile = mobile;
+        }
+
+        Ok(Some(emulation))
+    }
+
+    /// Validate the device scale factor within reasonable limits; an unbounded value
+    /// multiplies the raster buffer size the same way an unbounded height does
+    fn validate_scale_factor(value: f64) -> Result<f64, ScreenshotError> {
+        const MIN_SCALE: f64 = 0.1;
+        const MAX_SCALE: f64 = 5.0;
+
+        if value < MIN_SCALE {
+            return Err(ScreenshotError::InvalidUrl(format!(
+                "scale_factor must be at least {} (got {})",
+                MIN_SCALE, value
+            )));
+        }
+
+        if value > MAX_SCALE {
+            return Err(ScreenshotError::InvalidUrl(format!(
+                "scale_factor must be at most {} (got {})",
+                MAX_SCALE, value
+            )));
+        }
+
+        Ok(value)
+    }
+
+    /// Only `light` and `dark` are meaningful values for `prefers-color-scheme`
+    fn validate_color_scheme(color_scheme: Option<&str>) -> Result<(), ScreenshotError> {
+        match color_scheme {
+            None | Some("light") | Some("dark") => Ok(()),
+            Some(other) => Err(ScreenshotError::InvalidUrl(format!(
+                "color_scheme must be 'light' or 'dark' (got {})",
+                other
+            ))),
+        }
+    }
+
     /// Validate viewport dimensions within reasonable limits
     fn validate_dimension(value: u32, dimension_name: &str) -> Result<u32, ScreenshotError> {
         const MIN_SIZE: u32 = 320;  // Minimum reasonable size
@@ -168,33 +384,94 @@ impl ScreenshotService {
         width: u32,
         height: u32,
         wait_time: u64,
+        format: CaptureScreenshotFormatOption,
+        quality: Option<u8>,
+        full_page: bool,
+        clip: Option<ClipRegion>,
+        color_scheme: Option<&str>,
+        reduced_motion: bool,
+        device_emulation: Option<DeviceEmulation>,
     ) -> Result<Vec<u8>, ScreenshotError> {
+        // Maximum full-page height we'll ever render; beyond this we risk OOMing the Lambda sandbox
+        const MAX_FULL_PAGE_HEIGHT: u32 = 20_000;
+
         // Create new page
         println!("Creating new page...");
         let tab = browser
             .new_tab()
             .map_err(|e| ScreenshotError::Navigation(format!("Failed to create new tab: {}", e)))?;
 
-        // Set viewport size using emulation
-        println!("Setting viewport size to {}x{}", width, height);
+        // Set viewport size using emulation, applying any requested device preset/overrides
+        let (viewport_width, viewport_height, device_scale_factor, is_mobile) = match &device_emulation {
+            Some(d) => (d.width, d.height, d.device_scale_factor, d.mobile),
+            None => (width, height, 1.0, false),
+        };
+
+        println!("Setting viewport size to {}x{} (mobile: {})", viewport_width, viewport_height, is_mobile);
         tab.call_method(headless_chrome::protocol::cdp::Emulation::SetDeviceMetricsOverride {
-            width,
-            height,
-            device_scale_factor: 1.0,
-            mobile: false,
+            width: viewport_width,
+            height: viewport_height,
+            device_scale_factor,
+            mobile: is_mobile,
             scale: None,
-            screen_width: None,
-            screen_height: None,
+            screen_width: Some(viewport_width),
+            screen_height: Some(viewport_height),
             position_x: None,
             position_y: None,
             dont_set_visible_size: None,
-            screen_orientation: None,
+            screen_orientation: if is_mobile {
+                Some(headless_chrome::protocol::cdp::Emulation::ScreenOrientation {
+                    orientation_type: headless_chrome::protocol::cdp::Emulation::ScreenOrientationType::PortraitPrimary,
+                    angle: 0,
+                })
+            } else {
+                None
+            },
             viewport: None,
             device_posture: None,
             display_feature: None,
         })
         .map_err(|e| ScreenshotError::Navigation(format!("Failed to set viewport: {}", e)))?;
 
+        // Override the user-agent so responsive sites serve their mobile layout
+        if let Some(d) = &device_emulation {
+            if !d.user_agent.is_empty() {
+                println!("Overriding user agent for device emulation");
+                tab.call_method(headless_chrome::protocol::cdp::Network::SetUserAgentOverride {
+                    user_agent: d.user_agent.clone(),
+                    accept_language: None,
+                    platform: None,
+                    user_agent_metadata: None,
+                })
+                .map_err(|e| ScreenshotError::Navigation(format!("Failed to override user agent: {}", e)))?;
+            }
+        }
+
+        // Emulate media features so CSS media queries (dark mode, reduced motion) resolve
+        // against the requested values rather than the sandbox's defaults
+        if color_scheme.is_some() || reduced_motion {
+            let mut features = Vec::new();
+            if let Some(scheme) = color_scheme {
+                features.push(headless_chrome::protocol::cdp::Emulation::MediaFeature {
+                    name: "prefers-color-scheme".to_string(),
+                    value: scheme.to_string(),
+                });
+            }
+            if reduced_motion {
+                features.push(headless_chrome::protocol::cdp::Emulation::MediaFeature {
+                    name: "prefers-reduced-motion".to_string(),
+                    value: "reduce".to_string(),
+                });
+            }
+
+            println!("Setting emulated media features: {:?}", features);
+            tab.call_method(headless_chrome::protocol::cdp::Emulation::SetEmulatedMedia {
+                media: None,
+                features: Some(features),
+            })
+            .map_err(|e| ScreenshotError::Navigation(format!("Failed to set emulated media: {}", e)))?;
+        }
+
         // Navigate to URL
         println!("Navigating to URL: {}", url);
         tab.navigate_to(url)
@@ -211,11 +488,82 @@ impl ScreenshotService {
             std::thread::sleep(Duration::from_millis(wait_time));
         }
 
+        // Full-page mode: measure the document and stretch the viewport to match before capturing.
+        // A caller-supplied clip region takes precedence over full-page capture.
+        if full_page && clip.is_none() {
+            println!("Full-page mode requested, measuring document size...");
+            match tab.call_method(headless_chrome::protocol::cdp::Page::GetLayoutMetrics(None)) {
+                Ok(metrics) => {
+                    let content_size = metrics.css_content_size;
+                    let full_height = (content_size.height.round() as u32).min(MAX_FULL_PAGE_HEIGHT);
+                    let full_width = content_size.width.round() as u32;
+
+                    println!("Resizing viewport to full page size {}x{} (mobile: {})", full_width, full_height, is_mobile);
+                    tab.call_method(headless_chrome::protocol::cdp::Emulation::SetDeviceMetricsOverride {
+                        width: full_width,
+                        height: full_height,
+                        device_scale_factor,
+                        mobile: is_mobile,
+                        scale: None,
+                        screen_width: None,
+                        screen_height: None,
+                        position_x: None,
+                        position_y: None,
+                        dont_set_visible_size: None,
+                        screen_orientation: if is_mobile {
+                            Some(headless_chrome::protocol::cdp::Emulation::ScreenOrientation {
+                                orientation_type: headless_chrome::protocol::cdp::Emulation::ScreenOrientationType::PortraitPrimary,
+                                angle: 0,
+                            })
+                        } else {
+                            None
+                        },
+                        viewport: None,
+                        device_posture: None,
+                        display_feature: None,
+                    })
+                    .map_err(|e| ScreenshotError::Navigation(format!("Failed to resize to full page: {}", e)))?;
+                }
+                Err(e) => {
+                    println!("Failed to measure full page size ({}), falling back to viewport capture", e);
+                }
+            }
+        }
+
+        // If a clip region was requested, make sure it actually falls inside the rendered page
+        if let Some(c) = clip {
+            let metrics = tab
+                .call_method(headless_chrome::protocol::cdp::Page::GetLayoutMetrics(None))
+                .map_err(|e| ScreenshotError::Navigation(format!("Failed to measure page for clip: {}", e)))?;
+            let content_size = metrics.css_content_size;
+
+            if c.width <= 0.0 || c.height <= 0.0 {
+                return Err(ScreenshotError::InvalidUrl(
+                    "clip_width and clip_height must be positive".to_string(),
+                ));
+            }
+
+            if c.x < 0.0 || c.y < 0.0 || c.x + c.width > content_size.width || c.y + c.height > content_size.height {
+                return Err(ScreenshotError::InvalidUrl(format!(
+                    "clip region ({}, {}, {}, {}) falls outside the rendered page ({}x{})",
+                    c.x, c.y, c.width, c.height, content_size.width, content_size.height
+                )));
+            }
+        }
+
+        // Build the CDP clip viewport for a caller-supplied clip region, if any
+        let clip_viewport = clip.map(|c| headless_chrome::protocol::cdp::Page::Viewport {
+            x: c.x,
+            y: c.y,
+            width: c.width,
+            height: c.height,
+            scale: 1.0,
+        });
+
         // Take screenshot
-        println!("Taking screenshot...");
-        let screenshot_options = CaptureScreenshotFormatOption::Png;
+        println!("Taking screenshot as {:?} (quality: {:?}, clip: {:?})...", format, quality, clip_viewport);
         let screenshot_data = tab
-            .capture_screenshot(screenshot_options, None, None, true)
+            .capture_screenshot(format, quality, clip_viewport, true)
             .map_err(|e| ScreenshotError::Screenshot(format!("Screenshot capture failed: {}", e)))?;
         
         println!("Screenshot captured successfully, size: {} bytes", screenshot_data.len());
@@ -244,12 +592,13 @@ fn setup_lambda_env() {
 }
 
 /// Launch browser with retry logic to handle intermittent failures
-fn launch_browser_with_retry(chrome_path: PathBuf, chrome_args: Vec<&'static OsStr>, max_retries: u32) -> Result<Browser, String> {
+fn launch_browser_with_retry(chrome_path: PathBuf, chrome_args: Vec<OsString>, max_retries: u32) -> Result<Browser, String> {
     let mut last_error = String::new();
-    
+    let chrome_args: Vec<&OsStr> = chrome_args.iter().map(|a| a.as_os_str()).collect();
+
     for attempt in 1..=max_retries {
         println!("Browser launch attempt {} of {}", attempt, max_retries);
-        
+
         // Create fresh LaunchOptions for each attempt
         let fresh_options = LaunchOptions::default_builder()
             .path(Some(chrome_path.clone()))
@@ -293,4 +642,110 @@ fn find_chrome_executable() -> Option<PathBuf> {
         }
     }
     None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_device_emulation_keeps_caller_viewport_without_a_preset() {
+        let emulation = ScreenshotService::resolve_device_emulation(None, Some(2.0), None, 800, 600)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(emulation.width, 800);
+        assert_eq!(emulation.height, 600);
+        assert_eq!(emulation.device_scale_factor, 2.0);
+        assert!(!emulation.mobile);
+    }
+
+    #[test]
+    fn resolve_device_emulation_uses_preset_dimensions() {
+        let emulation = ScreenshotService::resolve_device_emulation(Some("iphone"), None, None, 800, 600)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(emulation.width, 390);
+        assert_eq!(emulation.height, 844);
+        assert!(emulation.mobile);
+    }
+
+    #[test]
+    fn resolve_device_emulation_rejects_unknown_device() {
+        let result = ScreenshotService::resolve_device_emulation(Some("nokia3310"), None, None, 800, 600);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn resolve_device_emulation_is_none_when_nothing_requested() {
+        let result = ScreenshotService::resolve_device_emulation(None, None, None, 800, 600).unwrap();
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn resolve_device_emulation_rejects_unbounded_scale_factor() {
+        let result = ScreenshotService::resolve_device_emulation(None, Some(500.0), None, 800, 600);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn validate_scale_factor_accepts_in_range_values() {
+        assert_eq!(ScreenshotService::validate_scale_factor(2.0).unwrap(), 2.0);
+    }
+
+    #[test]
+    fn validate_scale_factor_rejects_zero_and_negative() {
+        assert!(ScreenshotService::validate_scale_factor(0.0).is_err());
+        assert!(ScreenshotService::validate_scale_factor(-1.0).is_err());
+    }
+
+    #[test]
+    fn validate_scale_factor_rejects_too_large() {
+        assert!(ScreenshotService::validate_scale_factor(500.0).is_err());
+    }
+
+    #[test]
+    fn validate_quality_ignores_quality_for_png() {
+        let result =
+            ScreenshotService::validate_quality(Some(50), &CaptureScreenshotFormatOption::Png).unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn validate_quality_accepts_in_range_jpeg_quality() {
+        let result =
+            ScreenshotService::validate_quality(Some(80), &CaptureScreenshotFormatOption::Jpeg).unwrap();
+        assert_eq!(result, Some(80));
+    }
+
+    #[test]
+    fn validate_quality_rejects_out_of_range_quality() {
+        let result = ScreenshotService::validate_quality(Some(101), &CaptureScreenshotFormatOption::Jpeg);
+        assert!(result.is_err());
+    }
+
+    // These three cases all mutate the shared ALLOWED_CHROME_FLAGS env var, so they're merged
+    // into a single test run sequentially rather than left as separate #[test] fns, which
+    // `cargo test`'s default multi-threaded runner would otherwise race against each other.
+    #[test]
+    fn validate_extra_flags_respects_the_allowlist() {
+        env::set_var("ALLOWED_CHROME_FLAGS", "--lang,--force-device-scale-factor");
+        let allowed = ScreenshotService::validate_extra_flags(&[
+            "--lang=fr".to_string(),
+            "--force-device-scale-factor=2".to_string(),
+        ]);
+        assert_eq!(
+            allowed.unwrap(),
+            vec!["--lang=fr".to_string(), "--force-device-scale-factor=2".to_string()]
+        );
+
+        env::set_var("ALLOWED_CHROME_FLAGS", "--lang");
+        let rejected = ScreenshotService::validate_extra_flags(&["--user-data-dir=/tmp/evil".to_string()]);
+        assert!(rejected.is_err());
+
+        env::remove_var("ALLOWED_CHROME_FLAGS");
+        let rejected_when_unset = ScreenshotService::validate_extra_flags(&["--lang=fr".to_string()]);
+        assert!(rejected_when_unset.is_err());
+    }
 }
\ No newline at end of file